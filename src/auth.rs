@@ -0,0 +1,165 @@
+//! OAuth 2.0 authorization-code + refresh-token flow, used as an alternative
+//! to `pass show` for providers (Gmail et al.) that require XOAUTH2.
+//!
+//! The flow is only ever driven through [`ensure_access_token`]: it loads a
+//! cached refresh token from disk, refreshes the access token if it is
+//! missing or expired, and otherwise runs a one-time interactive
+//! authorization-code exchange via a local redirect listener. The refresh
+//! token, access token and expiry are cached as JSON in
+//! `OAuth2Config::cache_file`, and the access token is returned to the
+//! caller, which uses it for SASL XOAUTH2 rather than himalaya's `passwd_cmd`.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+};
+
+#[derive(Clone, Debug)]
+pub struct OAuth2Config {
+    pub client_id: String,
+    pub client_secret: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub redirect_host: String,
+    pub redirect_port: u16,
+    /// Path to the JSON file caching the refresh token and access token
+    /// expiry, e.g. `~/.cache/mailstat/oauth2-<email>.json`.
+    pub cache_file: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TokenCache {
+    refresh_token: String,
+    access_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    expires_in: i64,
+}
+
+/// Builds the SASL XOAUTH2 initial client response
+/// (`user=<email>\x01auth=Bearer <token>\x01\x01`), per
+/// <https://developers.google.com/gmail/imap/xoauth2-protocol>. The raw IMAP
+/// `AUTHENTICATE XOAUTH2` exchange in [`crate::sync`] sends this string;
+/// lettre's SMTP `Mechanism::Xoauth2` builds its own from the bare access
+/// token instead.
+pub fn xoauth2_sasl_response(email: &str, access_token: &str) -> String {
+    format!("user={}\x01auth=Bearer {}\x01\x01", email, access_token)
+}
+
+/// Returns a fresh access token, refreshing or re-authorizing as needed, and
+/// persists the updated cache to `cfg.cache_file`.
+pub fn ensure_access_token(cfg: &OAuth2Config) -> Result<String> {
+    let cache = load_cache(&cfg.cache_file);
+    let cache = match cache {
+        Some(cache) if cache.expires_at > Utc::now() => cache,
+        Some(cache) => refresh(cfg, &cache.refresh_token)?,
+        None => authorize(cfg)?,
+    };
+    save_cache(&cfg.cache_file, &cache)?;
+    Ok(cache.access_token)
+}
+
+fn load_cache(path: &str) -> Option<TokenCache> {
+    let data = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+fn save_cache(path: &str, cache: &TokenCache) -> Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string(cache)?)
+        .with_context(|| format!("writing oauth2 token cache to {}", path))
+}
+
+/// Runs the authorization-code flow: prints the authorization URL for the
+/// user to open in a browser, then blocks on a local listener for the
+/// redirect carrying `?code=...`.
+fn authorize(cfg: &OAuth2Config) -> Result<TokenCache> {
+    let redirect_uri = format!("http://{}:{}", cfg.redirect_host, cfg.redirect_port);
+    let auth_url = format!(
+        "{}?client_id={}&redirect_uri={}&response_type=code&access_type=offline&prompt=consent&scope=https://mail.google.com/",
+        cfg.auth_url, cfg.client_id, redirect_uri
+    );
+    println!("Open this URL to authorize mailstat, then approve access:\n{}", auth_url);
+
+    let listener = TcpListener::bind((cfg.redirect_host.as_str(), cfg.redirect_port))
+        .with_context(|| format!("binding oauth2 redirect listener on {}", redirect_uri))?;
+    let (stream, _) = listener.accept()?;
+    let code = read_redirect_code(stream)?;
+
+    let client = reqwest::blocking::Client::new();
+    let resp: TokenResponse = client
+        .post(&cfg.token_url)
+        .form(&[
+            ("client_id", cfg.client_id.as_str()),
+            ("client_secret", cfg.client_secret.as_str()),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("grant_type", "authorization_code"),
+        ])
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let refresh_token = resp
+        .refresh_token
+        .ok_or_else(|| anyhow!("token endpoint did not return a refresh_token"))?;
+    Ok(TokenCache {
+        refresh_token,
+        access_token: resp.access_token,
+        expires_at: Utc::now() + chrono::Duration::seconds(resp.expires_in),
+    })
+}
+
+fn refresh(cfg: &OAuth2Config, refresh_token: &str) -> Result<TokenCache> {
+    let client = reqwest::blocking::Client::new();
+    let resp: TokenResponse = client
+        .post(&cfg.token_url)
+        .form(&[
+            ("client_id", cfg.client_id.as_str()),
+            ("client_secret", cfg.client_secret.as_str()),
+            ("refresh_token", refresh_token),
+            ("grant_type", "refresh_token"),
+        ])
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(TokenCache {
+        refresh_token: resp.refresh_token.unwrap_or_else(|| refresh_token.to_string()),
+        access_token: resp.access_token,
+        expires_at: Utc::now() + chrono::Duration::seconds(resp.expires_in),
+    })
+}
+
+fn read_redirect_code(stream: std::net::TcpStream) -> Result<String> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // e.g. "GET /?code=4/0Ab...&scope=... HTTP/1.1"
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("malformed redirect request: {}", request_line))?;
+    let query = path.splitn(2, '?').nth(1).unwrap_or("");
+    let code = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("code="))
+        .ok_or_else(|| anyhow!("redirect did not include a code parameter"))?
+        .to_string();
+
+    let mut stream = stream;
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nmailstat authorized, you can close this tab.\n",
+    )?;
+    Ok(code)
+}
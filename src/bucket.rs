@@ -0,0 +1,56 @@
+//! Bucketing keys for `count_by_date`: calendar day (default), ISO week, or
+//! calendar month. Each variant sorts chronologically and renders a stable
+//! label, either a sensible default or a user-supplied strftime format.
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BucketKind {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BucketKey {
+    Day(NaiveDate),
+    Week(i32, u32),
+    Month(i32, u32),
+}
+
+impl BucketKey {
+    pub fn for_date(date: NaiveDate, kind: BucketKind) -> Self {
+        match kind {
+            BucketKind::Day => BucketKey::Day(date),
+            BucketKind::Week => {
+                let week = date.iso_week();
+                BucketKey::Week(week.year(), week.week())
+            }
+            BucketKind::Month => BucketKey::Month(date.year(), date.month()),
+        }
+    }
+
+    /// The first calendar day the bucket covers, used as the representative
+    /// date when rendering a custom `--datetime-fmt`.
+    fn representative_date(&self) -> NaiveDate {
+        match self {
+            BucketKey::Day(d) => *d,
+            BucketKey::Week(year, week) => {
+                NaiveDate::from_isoywd_opt(*year, *week, Weekday::Mon).unwrap()
+            }
+            BucketKey::Month(year, month) => NaiveDate::from_ymd_opt(*year, *month, 1).unwrap(),
+        }
+    }
+
+    pub fn label(&self, datetime_fmt: Option<&str>) -> String {
+        if let Some(fmt) = datetime_fmt {
+            return self.representative_date().format(fmt).to_string();
+        }
+        match self {
+            BucketKey::Day(d) => d.to_string(),
+            BucketKey::Week(year, week) => format!("{}-W{:02}", year, week),
+            BucketKey::Month(year, month) => format!("{}-{:02}", year, month),
+        }
+    }
+}
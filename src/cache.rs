@@ -0,0 +1,38 @@
+//! On-disk cache of fetched `Entry` values, versioned so incremental sync
+//! (see [`crate::sync`]) can persist the CONDSTORE bookkeeping alongside
+//! the entries themselves.
+
+use crate::Entry;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+/// The cache file format. Older caches are a bare `Vec<Entry>`; those are
+/// migrated in transparently by [`load_from_cache`] with no modseq, which
+/// forces a full resync on the next run.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    pub uidvalidity: Option<u32>,
+    pub highest_modseq: Option<u64>,
+    pub entries: Vec<Entry>,
+}
+
+pub async fn save_to_cache(cache_file: &str, cache: &Cache) -> Result<()> {
+    let file = File::create(cache_file)?;
+    serde_json::to_writer(file, cache)?;
+    Ok(())
+}
+
+pub async fn load_from_cache(cache_file: &str) -> Result<Cache> {
+    let data = std::fs::read_to_string(cache_file)?;
+    if let Ok(cache) = serde_json::from_str::<Cache>(&data) {
+        return Ok(cache);
+    }
+    // Pre-CONDSTORE cache format: a bare entries array.
+    let entries: Vec<Entry> = serde_json::from_str(&data)?;
+    Ok(Cache {
+        uidvalidity: None,
+        highest_modseq: None,
+        entries,
+    })
+}
@@ -0,0 +1,106 @@
+//! `~/.config/mailstat/config.toml`: one or more named IMAP/SMTP accounts
+//! plus default stats settings, so running reports for several mailboxes
+//! doesn't mean re-typing hosts and ports every time. `Args` (see
+//! `main.rs`) is layered on top as thin CLI overrides — see
+//! [`crate::resolve_account`].
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(rename = "account", default)]
+    pub accounts: Vec<Account>,
+    #[serde(default)]
+    pub stats: StatsConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Account {
+    pub email: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    pub imap_host: String,
+    pub imap_port: u16,
+    #[serde(default)]
+    pub imap_starttls: bool,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    /// Overrides the default `pass show mailstat/<email>` for this account.
+    #[serde(default)]
+    pub passwd_cmd: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StatsConfig {
+    #[serde(default)]
+    pub days: Option<u64>,
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub report_recipient: Option<String>,
+}
+
+/// `~/.config/mailstat/config.toml`, honoring `XDG_CONFIG_HOME`.
+pub fn default_path() -> PathBuf {
+    let config_home = std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
+        format!("{}/.config", std::env::var("HOME").unwrap_or_else(|_| ".".into()))
+    });
+    PathBuf::from(config_home).join("mailstat").join("config.toml")
+}
+
+/// Loads `path`. A missing file is treated as an empty config, so
+/// `--config` never has to be passed when the user has no config yet.
+pub fn load(path: &Path) -> Result<Config> {
+    match std::fs::read_to_string(path) {
+        Ok(data) => toml::from_str(&data).with_context(|| format!("parsing {:?}", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e).with_context(|| format!("reading {:?}", path)),
+    }
+}
+
+impl Config {
+    /// Finds the account named by `--account`, matching on email or
+    /// display name.
+    pub fn find_account(&self, name: &str) -> Option<&Account> {
+        self.accounts
+            .iter()
+            .find(|a| a.email == name || a.display_name.as_deref() == Some(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An `[[account]]` that only sets the fields without a sensible
+    /// default (`email`/`imap_host`/`imap_port`/`smtp_host`/`smtp_port`)
+    /// must still parse, and a `[stats]` table doesn't have to set any of
+    /// its keys either.
+    #[test]
+    fn parses_minimal_account_and_stats() {
+        let toml = r#"
+            [[account]]
+            email = "alice@example.com"
+            imap_host = "imap.example.com"
+            imap_port = 993
+            smtp_host = "smtp.example.com"
+            smtp_port = 587
+
+            [stats]
+        "#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.accounts.len(), 1);
+        let account = &config.accounts[0];
+        assert_eq!(account.email, "alice@example.com");
+        assert_eq!(account.display_name, None);
+        assert_eq!(account.passwd_cmd, None);
+        assert_eq!(config.stats.days, None);
+        assert_eq!(config.stats.folder, None);
+        assert_eq!(config.stats.filter, None);
+        assert_eq!(config.stats.report_recipient, None);
+    }
+}
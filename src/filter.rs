@@ -0,0 +1,261 @@
+//! A tiny boolean query language for restricting which `Entry` values feed
+//! the stats aggregations, e.g. `from:alice and (subject:"invoice" or domain:foo.com)`.
+//!
+//! Grammar:
+//!
+//! ```text
+//! query      = or_expr
+//! or_expr    = and_expr ("or" and_expr)*
+//! and_expr   = unary ("and" unary)*
+//! unary      = "not" unary | atom
+//! atom       = "(" query ")" | predicate
+//! predicate  = key ":" term
+//! key        = "from" | "subject" | "domain" | "before" | "after"
+//! term       = bare_word | '"' ... '"'
+//! ```
+
+use crate::Entry;
+use email_address_parser::EmailAddress;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Pred(Pred),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pred {
+    From(String),
+    Subject(String),
+    Domain(String),
+    Before(chrono::NaiveDate),
+    After(chrono::NaiveDate),
+}
+
+impl Expr {
+    /// Parse a filter query. Returns `None` for an empty/all-whitespace query,
+    /// which callers should treat as matching every entry.
+    pub fn parse(query: &str) -> Result<Option<Self>, ParseError> {
+        if query.trim().is_empty() {
+            return Ok(None);
+        }
+        let tokens = tokenize(query)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(ParseError(format!(
+                "unexpected trailing input at token {}",
+                parser.pos
+            )));
+        }
+        Ok(Some(expr))
+    }
+
+    pub fn eval(&self, entry: &Entry) -> bool {
+        match self {
+            Expr::And(l, r) => l.eval(entry) && r.eval(entry),
+            Expr::Or(l, r) => l.eval(entry) || r.eval(entry),
+            Expr::Not(e) => !e.eval(entry),
+            Expr::Pred(p) => p.eval(entry),
+        }
+    }
+}
+
+impl Pred {
+    fn eval(&self, entry: &Entry) -> bool {
+        match self {
+            Pred::From(term) => contains_ci(&entry.from_addr, term),
+            Pred::Subject(term) => contains_ci(&entry.subject, term),
+            Pred::Domain(term) => {
+                let domain = EmailAddress::parse(&entry.from_addr, None)
+                    .map(|a| a.get_domain().to_string())
+                    .unwrap_or_default();
+                contains_ci(&domain, term)
+            }
+            Pred::Before(date) => entry.date.date_naive() < *date,
+            Pred::After(date) => entry.date.date_naive() > *date,
+        }
+    }
+}
+
+fn contains_ci(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(pub String);
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "filter parse error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Key(String),
+    Colon,
+    Term(String),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = query.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        match c {
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            ':' => {
+                tokens.push(Token::Colon);
+                i += 1;
+            }
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    s.push(chars[i]);
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err(ParseError("unterminated quoted phrase".into()));
+                }
+                i += 1; // closing quote
+                tokens.push(Token::Term(s));
+            }
+            _ => {
+                let start = i;
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && chars[i] != '('
+                    && chars[i] != ')'
+                    && chars[i] != ':'
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.to_lowercase().as_str() {
+                    "and" => tokens.push(Token::And),
+                    "or" => tokens.push(Token::Or),
+                    "not" => tokens.push(Token::Not),
+                    "from" | "subject" | "domain" | "before" | "after" => {
+                        tokens.push(Token::Key(word.to_lowercase()))
+                    }
+                    _ => tokens.push(Token::Term(word)),
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn bump(&mut self) -> Option<&Token> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_and()?;
+        while matches!(self.peek(), Some(Token::Or)) {
+            self.bump();
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        while matches!(self.peek(), Some(Token::And)) {
+            self.bump();
+            let rhs = self.parse_unary()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if matches!(self.peek(), Some(Token::Not)) {
+            self.bump();
+            let inner = self.parse_unary()?;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, ParseError> {
+        match self.bump() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.bump() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(ParseError("expected closing ')'".into())),
+                }
+            }
+            Some(Token::Key(key)) => {
+                let key = key.clone();
+                match self.bump() {
+                    Some(Token::Colon) => {}
+                    _ => return Err(ParseError(format!("expected ':' after '{}'", key))),
+                }
+                let term = match self.bump() {
+                    Some(Token::Term(t)) => t.clone(),
+                    other => {
+                        return Err(ParseError(format!(
+                            "expected a term after '{}:', got {:?}",
+                            key, other
+                        )))
+                    }
+                };
+                match key.as_str() {
+                    "from" => Ok(Expr::Pred(Pred::From(term))),
+                    "subject" => Ok(Expr::Pred(Pred::Subject(term))),
+                    "domain" => Ok(Expr::Pred(Pred::Domain(term))),
+                    "before" => Ok(Expr::Pred(Pred::Before(parse_date(&term)?))),
+                    "after" => Ok(Expr::Pred(Pred::After(parse_date(&term)?))),
+                    other => Err(ParseError(format!("unknown predicate key '{}'", other))),
+                }
+            }
+            other => Err(ParseError(format!(
+                "expected '(', 'not', or a predicate, got {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+fn parse_date(s: &str) -> Result<chrono::NaiveDate, ParseError> {
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|e| ParseError(format!("invalid date '{}': {}", s, e)))
+}
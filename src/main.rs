@@ -1,6 +1,16 @@
+mod auth;
+mod bucket;
+mod cache;
+mod config;
+mod filter;
+mod source;
+mod sync;
+
 use anyhow::Result;
-use chrono::{DateTime, Datelike, Days, FixedOffset, Local, NaiveDate};
-use clap::Parser;
+use auth::OAuth2Config;
+use bucket::{BucketKey, BucketKind};
+use chrono::{DateTime, Days, FixedOffset, Local};
+use clap::{Parser, ValueEnum};
 use email_address_parser::EmailAddress;
 use env_logger;
 use himalaya_lib::{
@@ -9,17 +19,17 @@ use himalaya_lib::{
 };
 use lettre::{
     message::{Attachment, Body, MultiPart, SinglePart},
-    Message,
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        SmtpTransport,
+    },
+    Message, Transport,
 };
 use once_cell::sync::Lazy;
 use plotters::prelude::*;
 use prettytable::{format, row, Table};
 use serde::{Deserialize, Serialize, Serializer};
-use std::{
-    collections::{HashMap, HashSet},
-    fs::File,
-    io::Write,
-};
+use std::collections::{HashMap, HashSet};
 
 static CLEARLY_ERRONEOUS_DATE: Lazy<DateTime<FixedOffset>> =
     Lazy::new(|| DateTime::parse_from_rfc3339("1980-01-01T00:00:00+00:00").unwrap());
@@ -27,67 +37,322 @@ static CLEARLY_ERRONEOUS_DATE: Lazy<DateTime<FixedOffset>> =
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Required unless `--account` or `--all-accounts` selects account(s)
+    /// from the config file instead.
     #[arg(short, long)]
-    email: String,
-    #[arg(long, default_value = "imap.gmail.com")]
-    imap_host: String,
-    #[arg(long, default_value = "993")]
-    imap_port: u16,
+    email: Option<String>,
+    #[arg(long)]
+    imap_host: Option<String>,
+    #[arg(long)]
+    imap_port: Option<u16>,
     #[arg(long)]
     imap_starttls: bool,
-    #[arg(long, default_value = "smtp.gmail.com")]
-    smtp_host: String,
-    #[arg(long, default_value = "587")]
-    smtp_port: u16,
-    #[arg(short, long, default_value = "14")]
-    days: u64,
+    #[arg(long)]
+    smtp_host: Option<String>,
+    #[arg(long)]
+    smtp_port: Option<u16>,
+    #[arg(short, long)]
+    days: Option<u64>,
     #[arg(long)]
     cache: Option<String>,
     #[arg(long)]
     send_report_to_email: bool,
+    /// Boolean query restricting which entries feed the stats, e.g.
+    /// `from:alice and (subject:"invoice" or domain:foo.com)`.
+    #[arg(long)]
+    filter: Option<String>,
+    /// IMAP folder to fetch envelopes from. Defaults to `[stats].folder` in
+    /// the config file, or `INBOX` if that's unset too.
+    #[arg(long)]
+    folder: Option<String>,
+    /// Path to the TOML config file. Defaults to
+    /// `$XDG_CONFIG_HOME/mailstat/config.toml`.
+    #[arg(long)]
+    config: Option<String>,
+    /// Select one `[[account]]` from the config file by email or
+    /// display-name.
+    #[arg(long)]
+    account: Option<String>,
+    /// Run the report against every configured account, aggregating all
+    /// their entries into a single report.
+    #[arg(long)]
+    all_accounts: bool,
+    /// Authentication method for both IMAP and SMTP.
+    #[arg(long, value_enum, default_value_t = AuthMethod::Password)]
+    auth: AuthMethod,
+    #[arg(long, default_value = "")]
+    oauth2_client_id: String,
+    #[arg(long, default_value = "")]
+    oauth2_client_secret: String,
+    #[arg(long, default_value = "https://accounts.google.com/o/oauth2/v2/auth")]
+    oauth2_auth_url: String,
+    #[arg(long, default_value = "https://oauth2.googleapis.com/token")]
+    oauth2_token_url: String,
+    #[arg(long, default_value = "localhost")]
+    oauth2_redirect_host: String,
+    #[arg(long, default_value = "9999")]
+    oauth2_redirect_port: u16,
+    /// Where to read envelopes from: `imap` (default), `maildir:/path`, or
+    /// `mbox:/path`.
+    #[arg(long, default_value = "imap")]
+    source: source::Source,
+    /// Write all entries matching the date window (and `--filter`) to FILE
+    /// as a valid mbox file. Only entries from a local source carry the raw
+    /// message bytes this needs.
+    #[arg(long)]
+    export_mbox: Option<String>,
+    /// strftime format controlling how bucket labels render in the CSV
+    /// output and the plot's x-axis. Defaults to an ISO-ish label per
+    /// `--bucket` kind.
+    #[arg(long)]
+    datetime_fmt: Option<String>,
+    /// Convert each entry's date to the machine's local timezone before
+    /// bucketing, rather than using the timezone it was stored with.
+    #[arg(long)]
+    local_datetime: bool,
+    /// Group counts by calendar day (default), ISO week, or calendar month.
+    #[arg(long, value_enum, default_value_t = BucketKind::Day)]
+    bucket: BucketKind,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AuthMethod {
+    Password,
+    Oauth2,
+}
+
+/// IMAP/SMTP settings for one account, fully merged from `Args` overrides on
+/// top of the config account selected by `--account`/`--all-accounts`.
+struct ResolvedAccount {
+    email: String,
+    imap_host: String,
+    imap_port: u16,
+    imap_starttls: bool,
+    smtp_host: String,
+    smtp_port: u16,
+    passwd_cmd_override: Option<String>,
+}
+
+fn resolve_account(args: &Args, account: Option<&config::Account>) -> Result<ResolvedAccount> {
+    let email = args
+        .email
+        .clone()
+        .or_else(|| account.map(|a| a.email.clone()))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "--email is required unless --account or --all-accounts selects one from the config file"
+            )
+        })?;
+    Ok(ResolvedAccount {
+        email,
+        imap_host: args
+            .imap_host
+            .clone()
+            .or_else(|| account.map(|a| a.imap_host.clone()))
+            .unwrap_or_else(|| "imap.gmail.com".to_string()),
+        imap_port: args
+            .imap_port
+            .or_else(|| account.map(|a| a.imap_port))
+            .unwrap_or(993),
+        imap_starttls: args.imap_starttls || account.map_or(false, |a| a.imap_starttls),
+        smtp_host: args
+            .smtp_host
+            .clone()
+            .or_else(|| account.map(|a| a.smtp_host.clone()))
+            .unwrap_or_else(|| "smtp.gmail.com".to_string()),
+        smtp_port: args
+            .smtp_port
+            .or_else(|| account.map(|a| a.smtp_port))
+            .unwrap_or(587),
+        passwd_cmd_override: account.and_then(|a| a.passwd_cmd.clone()),
+    })
+}
+
+/// Which config accounts to process: all of them (`--all-accounts`), one
+/// named one (`--account NAME`), or none (CLI-only, single account via
+/// `--email`).
+fn selected_accounts<'a>(
+    args: &Args,
+    config: &'a config::Config,
+) -> Result<Vec<Option<&'a config::Account>>> {
+    if args.all_accounts {
+        if config.accounts.is_empty() {
+            return Err(anyhow::anyhow!(
+                "--all-accounts given but the config file has no [[account]] entries"
+            ));
+        }
+        return Ok(config.accounts.iter().map(Some).collect());
+    }
+    if let Some(name) = &args.account {
+        let account = config
+            .find_account(name)
+            .ok_or_else(|| anyhow::anyhow!("no account named '{}' in the config file", name))?;
+        return Ok(vec![Some(account)]);
+    }
+    Ok(vec![None])
+}
+
+/// Builds the himalaya account/IMAP configs for `account`, connects, and
+/// runs the cached/CONDSTORE-incremental/full-pagination fetch exactly as a
+/// single-account run would.
+async fn fetch_imap_entries(
+    args: &Args,
+    account: &ResolvedAccount,
+    until: DateTime<Local>,
+    folder: &str,
+) -> Result<Vec<Entry>> {
+    let credential = match &account.passwd_cmd_override {
+        Some(cmd) => Credential::Passwd(cmd.clone()),
+        None => credential_for(args, &account.email)?,
+    };
+
+    let cache_file = args
+        .cache
+        .as_ref()
+        .map(|base| per_account_cache_path(base, &account.email, args.all_accounts));
+
+    let mut mailbox_cache = cache::Cache::default();
+    if let Some(cache_file) = &cache_file {
+        if let Ok(cache) = cache::load_from_cache(cache_file).await {
+            mailbox_cache = cache;
+        } else {
+            eprintln!("Cache file {} not found, will create new", cache_file);
+        }
+    }
+    let mut entries = std::mem::take(&mut mailbox_cache.entries);
+    let mut message_ids: HashSet<String> = entries.iter().map(|e| e.message_id.clone()).collect();
+    let message_count = message_ids.len();
+    println!("[{}] Messages cached: {}", account.email, message_count);
+
+    let mut condstore_state = None;
+    if let (Some(uidvalidity), Some(highest_modseq)) =
+        (mailbox_cache.uidvalidity, mailbox_cache.highest_modseq)
+    {
+        match sync_condstore(
+            &account.imap_host,
+            account.imap_port,
+            &account.email,
+            &credential,
+            folder,
+            uidvalidity,
+            highest_modseq,
+        ) {
+            Ok(Some((new_state, changed))) => {
+                eprintln!(
+                    "CONDSTORE sync: {} envelope(s) changed since modseq {}",
+                    changed.len(),
+                    highest_modseq
+                );
+                for entry in changed {
+                    if entry.date < *CLEARLY_ERRONEOUS_DATE {
+                        continue;
+                    }
+                    if !message_ids.contains(&entry.message_id) {
+                        message_ids.insert(entry.message_id.clone());
+                        entries.push(entry);
+                    }
+                }
+                condstore_state = Some(new_state);
+            }
+            Ok(None) => {
+                eprintln!("UIDVALIDITY changed, falling back to a full resync");
+                entries.clear();
+                message_ids.clear();
+            }
+            Err(e) => {
+                eprintln!(
+                    "CONDSTORE sync unavailable ({}), falling back to full pagination",
+                    e
+                );
+            }
+        }
+    }
+
+    if condstore_state.is_none() {
+        entries = match &credential {
+            Credential::Passwd(passwd_cmd) => full_pagination_himalaya(
+                account,
+                passwd_cmd,
+                folder,
+                until,
+                entries,
+                &mut message_ids,
+            )?,
+            Credential::OAuth2(access_token) => full_fetch_oauth2(
+                account,
+                access_token,
+                folder,
+                until,
+                entries,
+                &mut message_ids,
+            )?,
+        };
+        // Record a CONDSTORE baseline for next run, best-effort.
+        condstore_state = sync_condstore_baseline(
+            &account.imap_host,
+            account.imap_port,
+            &account.email,
+            &credential,
+            folder,
+        )
+        .unwrap_or(None);
+    }
+
+    eprintln!(
+        "[{}] Loaded {} envelopes, {} new",
+        account.email,
+        entries.len(),
+        message_ids.len() - message_count
+    );
+    if let Some(cache_file) = &cache_file {
+        eprintln!("Saving to cache file {}...", cache_file);
+        let cache = cache::Cache {
+            uidvalidity: condstore_state.as_ref().map(|s| s.uidvalidity),
+            highest_modseq: condstore_state.as_ref().map(|s| s.highest_modseq),
+            entries: entries.clone(),
+        };
+        cache::save_to_cache(cache_file, &cache).await?;
+    }
+    Ok(entries)
+}
+
+/// Full himalaya-paginated fetch, unchanged from before OAuth2 support: only
+/// `Credential::Passwd` accounts take this path, since himalaya's backend
+/// always authenticates via plain `LOGIN`.
+fn full_pagination_himalaya(
+    account: &ResolvedAccount,
+    passwd_cmd: &str,
+    folder: &str,
+    until: DateTime<Local>,
+    mut entries: Vec<Entry>,
+    message_ids: &mut HashSet<String>,
+) -> Result<Vec<Entry>> {
     let account_cfg = AccountConfig {
-        email: args.email.clone(),
+        email: account.email.clone(),
         email_sender: Smtp(SmtpConfig {
-            host: args.smtp_host,
-            port: args.smtp_port,
+            host: account.smtp_host.clone(),
+            port: account.smtp_port,
             ssl: Some(true),
             starttls: Some(true),
             insecure: Some(false),
-            login: args.email.clone(),
-            passwd_cmd: format!("pass show mailstat/{}", args.email),
+            login: account.email.clone(),
+            passwd_cmd: passwd_cmd.to_string(),
         }),
         ..Default::default()
     };
     let imap_cfg = ImapConfig {
-        host: args.imap_host,
-        port: args.imap_port,
-        starttls: Some(args.imap_starttls),
-        login: args.email.clone(),
-        passwd_cmd: format!("pass show mailstat/{}", args.email),
+        host: account.imap_host.clone(),
+        port: account.imap_port,
+        starttls: Some(account.imap_starttls),
+        login: account.email.clone(),
+        passwd_cmd: passwd_cmd.to_string(),
         ..Default::default()
     };
     let backend_cfg = BackendConfig::Imap(imap_cfg);
     let backend = BackendBuilder::new()
         .build(&account_cfg, &backend_cfg)
         .unwrap();
-    let until = Local::now().checked_sub_days(Days::new(args.days)).unwrap();
-    let mut entries: Vec<Entry> = Vec::new();
-    if let Some(cache_file) = &args.cache {
-        if let Ok(cache) = load_from_cache(cache_file).await {
-            entries = cache;
-        } else {
-            eprintln!("Cache file {} not found, will create new", cache_file);
-        }
-    }
-    let mut message_ids: HashSet<String> = entries.iter().map(|e| e.message_id.clone()).collect();
-    let message_count = message_ids.len();
-    println!("Messages cached: {}", message_count);
+
     let mut i = 0;
     // let folders = backend.list_folders()?;
     // println!("Folders: {:#?}", folders);
@@ -96,7 +361,7 @@ async fn main() -> Result<()> {
             eprintln!("Last date: {}", entry.date);
         }
         eprintln!("Loading page {}...", i);
-        let page = backend.list_envelopes("INBOX", 100, i).unwrap();
+        let page = backend.list_envelopes(folder, 100, i).unwrap();
         if page.is_empty() {
             break;
         }
@@ -115,25 +380,133 @@ async fn main() -> Result<()> {
         }
         i += 1;
     }
-    eprintln!(
-        "Loaded {} envelopes, {} new",
-        entries.len(),
-        message_ids.len() - message_count
+    Ok(entries)
+}
+
+/// Full fetch for `AuthMethod::Oauth2` accounts: himalaya's backend can't
+/// authenticate these, so a raw SASL XOAUTH2 session fetches every envelope
+/// in one shot (see [`sync::list_all_envelopes`]) and the date window is
+/// applied here afterward instead of per-page.
+fn full_fetch_oauth2(
+    account: &ResolvedAccount,
+    access_token: &str,
+    folder: &str,
+    until: DateTime<Local>,
+    mut entries: Vec<Entry>,
+    message_ids: &mut HashSet<String>,
+) -> Result<Vec<Entry>> {
+    let mut session = sync::connect_xoauth2(
+        &account.imap_host,
+        account.imap_port,
+        &account.email,
+        access_token,
+    )?;
+    let fetched = sync::list_all_envelopes(&mut session, folder)?;
+    for entry in fetched {
+        if entry.date < *CLEARLY_ERRONEOUS_DATE || entry.date < until {
+            continue;
+        }
+        if !message_ids.contains(&entry.message_id) {
+            message_ids.insert(entry.message_id.clone());
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// `--all-accounts` shares one `--cache BASE` across accounts by suffixing
+/// each account's cache with its email, so they don't clobber each other.
+fn per_account_cache_path(base: &str, email: &str, all_accounts: bool) -> String {
+    if all_accounts {
+        format!("{}.{}", base, email)
+    } else {
+        base.to_string()
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    let config_path = args
+        .config
+        .as_ref()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(config::default_path);
+    let config = config::load(&config_path)?;
+
+    let until = Local::now()
+        .checked_sub_days(Days::new(args.days.or(config.stats.days).unwrap_or(14)))
+        .unwrap();
+    let filter_str = args.filter.clone().or_else(|| config.stats.filter.clone());
+    let query = filter::Expr::parse(filter_str.as_deref().unwrap_or(""))
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    let folder = args
+        .folder
+        .clone()
+        .or_else(|| config.stats.folder.clone())
+        .unwrap_or_else(|| "INBOX".to_string());
+
+    let entries = match &args.source {
+        source::Source::Imap => {
+            let accounts = selected_accounts(&args, &config)?;
+            let mut entries = Vec::new();
+            for account in accounts {
+                let resolved = resolve_account(&args, account)?;
+                entries.extend(fetch_imap_entries(&args, &resolved, until, &folder).await?);
+            }
+            entries
+        }
+        source::Source::Maildir(path) => {
+            use source::EnvelopeSource;
+            source::MaildirSource { path: path.clone() }.list_entries()?
+        }
+        source::Source::Mbox(path) => {
+            use source::EnvelopeSource;
+            source::MboxSource { path: path.clone() }.list_entries()?
+        }
+    };
+
+    let matches_query = |e: &&Entry| query.as_ref().map_or(true, |q| q.eval(e));
+    print_counts_by_date(
+        entries.iter().filter(|e| e.date > until).filter(matches_query),
+        args.datetime_fmt.as_deref(),
+        args.local_datetime,
+        args.bucket,
     );
-    if let Some(cache_file) = &args.cache {
-        eprintln!("Saving to cache file {}...", cache_file);
-        save_to_cache(cache_file, &entries).await?;
+    let table_by_domain =
+        table_counts_by_domain(entries.iter().filter(|e| e.date > until).filter(matches_query));
+    graph_counts_by_date(
+        entries.iter().filter(matches_query),
+        args.datetime_fmt.as_deref(),
+        args.local_datetime,
+        args.bucket,
+    );
+    if let Some(export_file) = &args.export_mbox {
+        let matching: Vec<&Entry> = entries
+            .iter()
+            .filter(|e| e.date > until)
+            .filter(matches_query)
+            .collect();
+        eprintln!("Exporting {} entries to {}...", matching.len(), export_file);
+        source::export_mbox(std::path::Path::new(export_file), &matching)?;
     }
-    print_counts_by_date(entries.iter().filter(|e| e.date > until));
-    let table_by_domain = table_counts_by_domain(entries.iter().filter(|e| e.date > until));
-    graph_counts_by_date(entries.iter());
     if args.send_report_to_email {
-        let mut sender = SenderBuilder::build(&account_cfg).unwrap();
+        let primary = resolve_account(&args, selected_accounts(&args, &config)?[0])?;
+        let credential = match &primary.passwd_cmd_override {
+            Some(cmd) => Credential::Passwd(cmd.clone()),
+            None => credential_for(&args, &primary.email)?,
+        };
+        let recipient = config
+            .stats
+            .report_recipient
+            .clone()
+            .unwrap_or_else(|| primary.email.clone());
         let image_by_date = std::fs::read("var/count-by-date.png")?;
         let image_by_date_body = Body::new(image_by_date);
         let email = Message::builder()
-            .from(args.email.parse().unwrap())
-            .to(args.email.parse().unwrap())
+            .from(primary.email.parse().unwrap())
+            .to(recipient.parse().unwrap())
             .subject("mailstat report")
             .multipart(
                 MultiPart::mixed()
@@ -146,60 +519,174 @@ async fn main() -> Result<()> {
                             .body(image_by_date_body, "image/png".parse().unwrap()),
                     ),
             )?;
-        sender.send(&email.formatted()).unwrap();
+        match &credential {
+            Credential::Passwd(passwd_cmd) => {
+                let account_cfg = AccountConfig {
+                    email: primary.email.clone(),
+                    email_sender: Smtp(SmtpConfig {
+                        host: primary.smtp_host.clone(),
+                        port: primary.smtp_port,
+                        ssl: Some(true),
+                        starttls: Some(true),
+                        insecure: Some(false),
+                        login: primary.email.clone(),
+                        passwd_cmd: passwd_cmd.clone(),
+                    }),
+                    ..Default::default()
+                };
+                let mut sender = SenderBuilder::build(&account_cfg).unwrap();
+                sender.send(&email.formatted()).unwrap();
+            }
+            Credential::OAuth2(access_token) => {
+                // himalaya's `SenderBuilder` only speaks plain `LOGIN`, same as
+                // its IMAP backend, so OAuth2 accounts send over a raw lettre
+                // transport using its native XOAUTH2 mechanism instead.
+                let transport = SmtpTransport::relay(&primary.smtp_host)
+                    .unwrap()
+                    .port(primary.smtp_port)
+                    .credentials(Credentials::new(primary.email.clone(), access_token.clone()))
+                    .authentication(vec![Mechanism::Xoauth2])
+                    .build();
+                transport.send(&email).unwrap();
+            }
+        }
     }
     Ok(())
 }
 
-fn count_by_date<'a>(entries: impl Iterator<Item = &'a Entry>) -> Vec<(NaiveDate, usize)> {
-    let mut counts: HashMap<NaiveDate, usize> = HashMap::new();
+/// How a run authenticates its IMAP/SMTP sessions. `Passwd` is a
+/// `passwd_cmd` shell command, unchanged from before OAuth2 support and the
+/// only variant himalaya's backend understands (it always issues plain
+/// `LOGIN`). `OAuth2` carries a bare access token: providers that require
+/// XOAUTH2 (Gmail included) reject plain-password login for OAuth-scoped
+/// accounts, so this variant bypasses himalaya and drives a raw SASL
+/// `AUTHENTICATE XOAUTH2` exchange for IMAP (`sync::connect_xoauth2`) and
+/// lettre's `Mechanism::Xoauth2` for SMTP instead.
+enum Credential {
+    Passwd(String),
+    OAuth2(String),
+}
+
+/// Builds the credential used to authenticate `email`'s IMAP/SMTP sessions.
+/// For `AuthMethod::Password` this is unchanged from before (`pass show`).
+/// For `AuthMethod::Oauth2` it eagerly refreshes (or interactively obtains)
+/// an access token and caches it to disk.
+fn credential_for(args: &Args, email: &str) -> Result<Credential> {
+    match args.auth {
+        AuthMethod::Password => Ok(Credential::Passwd(format!("pass show mailstat/{}", email))),
+        AuthMethod::Oauth2 => {
+            let oauth2_cfg = OAuth2Config {
+                client_id: args.oauth2_client_id.clone(),
+                client_secret: args.oauth2_client_secret.clone(),
+                auth_url: args.oauth2_auth_url.clone(),
+                token_url: args.oauth2_token_url.clone(),
+                redirect_host: args.oauth2_redirect_host.clone(),
+                redirect_port: args.oauth2_redirect_port,
+                cache_file: format!("{}/mailstat/oauth2-{}.json", dirs_cache_home(), email),
+            };
+            let access_token = auth::ensure_access_token(&oauth2_cfg)?;
+            Ok(Credential::OAuth2(access_token))
+        }
+    }
+}
+
+/// Opens a raw IMAP session the way `credential` demands: `LOGIN` with a
+/// resolved `passwd_cmd`, or SASL `XOAUTH2` with a bare access token.
+fn connect_for(host: &str, port: u16, login: &str, credential: &Credential) -> Result<sync::ImapSession> {
+    match credential {
+        Credential::Passwd(passwd_cmd) => {
+            let password = resolve_passwd_cmd(passwd_cmd)?;
+            sync::connect(host, port, login, &password)
+        }
+        Credential::OAuth2(access_token) => sync::connect_xoauth2(host, port, login, access_token),
+    }
+}
+
+fn dirs_cache_home() -> String {
+    std::env::var("XDG_CACHE_HOME").unwrap_or_else(|_| {
+        format!("{}/.cache", std::env::var("HOME").unwrap_or_else(|_| ".".into()))
+    })
+}
+
+fn count_by_date<'a>(
+    entries: impl Iterator<Item = &'a Entry>,
+    local_datetime: bool,
+    bucket_kind: BucketKind,
+) -> Vec<(BucketKey, usize)> {
+    let mut counts: HashMap<BucketKey, usize> = HashMap::new();
     for entry in entries {
         if entry.date < *CLEARLY_ERRONEOUS_DATE {
             continue;
         }
-        let date = NaiveDate::from_ymd_opt(entry.date.year(), entry.date.month(), entry.date.day())
-            .unwrap();
-        let count = counts.entry(date).or_insert(0);
+        let date_naive = if local_datetime {
+            entry.date.with_timezone(&Local).date_naive()
+        } else {
+            entry.date.date_naive()
+        };
+        let key = BucketKey::for_date(date_naive, bucket_kind);
+        let count = counts.entry(key).or_insert(0);
         *count += 1;
     }
-    let mut sorted: Vec<(NaiveDate, usize)> = counts.into_iter().collect();
+    let mut sorted: Vec<(BucketKey, usize)> = counts.into_iter().collect();
     sorted.sort();
     sorted
 }
 
-fn print_counts_by_date<'a>(entries: impl Iterator<Item = &'a Entry>) {
-    let counts = count_by_date(entries);
+fn print_counts_by_date<'a>(
+    entries: impl Iterator<Item = &'a Entry>,
+    datetime_fmt: Option<&str>,
+    local_datetime: bool,
+    bucket_kind: BucketKind,
+) {
+    let counts = count_by_date(entries, local_datetime, bucket_kind);
     println!("date,count");
-    for (date, count) in counts.iter() {
-        println!("{},{},{}", date, date.weekday(), count);
+    for (key, count) in counts.iter() {
+        println!("{},{}", key.label(datetime_fmt), count);
     }
 }
 
-fn graph_counts_by_date<'a>(entries: impl Iterator<Item = &'a Entry>) {
-    let counts = count_by_date(entries);
-    let min_date = counts.first().unwrap().0;
-    let max_date = counts.last().unwrap().0;
+fn graph_counts_by_date<'a>(
+    entries: impl Iterator<Item = &'a Entry>,
+    datetime_fmt: Option<&str>,
+    local_datetime: bool,
+    bucket_kind: BucketKind,
+) {
+    let counts = count_by_date(entries, local_datetime, bucket_kind);
+    if counts.is_empty() {
+        println!("No data to plot.");
+        return;
+    }
+    let labels: Vec<String> = counts.iter().map(|(k, _)| k.label(datetime_fmt)).collect();
     let max_count = *counts.iter().map(|(_, c)| c).max().unwrap();
+    let last_index = counts.len().saturating_sub(1) as i32;
     let root = BitMapBackend::new("var/count-by-date.png", (1024, 768)).into_drawing_area();
     root.fill(&WHITE).unwrap();
     let mut chart = ChartBuilder::on(&root)
         .caption("Emails by date", ("sans-serif", 20).into_font())
         .margin(5)
-        .x_label_area_size(30)
+        .x_label_area_size(40)
         .y_label_area_size(30)
-        .build_cartesian_2d(min_date..max_date, 0..max_count)
+        .build_cartesian_2d(0..last_index, 0..max_count)
+        .unwrap();
+    chart
+        .configure_mesh()
+        .x_label_formatter(&|x| labels.get(*x as usize).cloned().unwrap_or_default())
+        .draw()
         .unwrap();
-    chart.configure_mesh().draw().unwrap();
     chart
-        .draw_series(LineSeries::new(counts.iter().map(|(d, c)| (*d, *c)), &RED))
+        .draw_series(LineSeries::new(
+            counts.iter().enumerate().map(|(i, (_, c))| (i as i32, *c)),
+            &RED,
+        ))
         .unwrap();
 }
 
 fn count_by_domain<'a>(entries: impl Iterator<Item = &'a Entry>) -> HashMap<String, usize> {
     let mut counts: HashMap<String, usize> = HashMap::new();
     for entry in entries {
-        let sender = EmailAddress::parse(&entry.from_addr, None).unwrap();
-        let domain = sender.get_domain().to_string();
+        let domain = EmailAddress::parse(&entry.from_addr, None)
+            .map(|a| a.get_domain().to_string())
+            .unwrap_or_default();
         let count = counts.entry(domain).or_insert(0);
         *count += 1;
     }
@@ -221,28 +708,37 @@ fn table_counts_by_domain<'a>(entries: impl Iterator<Item = &'a Entry>) -> Table
     table
 }
 
-fn serialize_date<S: Serializer>(date: &DateTime<Local>, s: S) -> Result<S::Ok, S::Error> {
+fn serialize_date<S: Serializer>(date: &DateTime<FixedOffset>, s: S) -> Result<S::Ok, S::Error> {
     s.serialize_str(&date.to_rfc3339())
 }
 
-fn deserialize_date<'de, D: serde::Deserializer<'de>>(d: D) -> Result<DateTime<Local>, D::Error> {
+fn deserialize_date<'de, D: serde::Deserializer<'de>>(
+    d: D,
+) -> Result<DateTime<FixedOffset>, D::Error> {
     let s = String::deserialize(d)?;
     // CR: how do we get a D::Error here?
-    let dt = DateTime::parse_from_rfc3339(&s).unwrap();
-    Ok(dt.with_timezone(&Local))
+    Ok(DateTime::parse_from_rfc3339(&s).unwrap())
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Entry {
     pub id: String,
     pub message_id: String,
     pub from_addr: String,
     pub subject: String,
+    /// Kept in the offset it was sent with rather than collapsed to
+    /// `Local`, so `--local-datetime` has something to actually convert —
+    /// see `count_by_date`.
     #[serde(
         serialize_with = "serialize_date",
         deserialize_with = "deserialize_date"
     )]
-    pub date: DateTime<Local>,
+    pub date: DateTime<FixedOffset>,
+    /// Raw message bytes, only ever populated for local (Maildir/mbox)
+    /// sources so `--export-mbox` has something to write back out. Never
+    /// cached.
+    #[serde(skip)]
+    pub raw: Option<Vec<u8>>,
 }
 
 impl From<&Envelope> for Entry {
@@ -252,19 +748,58 @@ impl From<&Envelope> for Entry {
             message_id: envelope.message_id.clone(),
             from_addr: envelope.from.addr.clone(),
             subject: envelope.subject.clone(),
-            date: envelope.date.clone(),
+            // himalaya's `Envelope::date` is already normalized to `Local`
+            // by the time we see it, so this is the machine's local offset,
+            // not the envelope's original one — the best available here.
+            date: envelope.date.fixed_offset(),
+            raw: None,
         }
     }
 }
 
-async fn save_to_cache(cache_file: &str, entries: &Vec<Entry>) -> Result<()> {
-    let mut file = File::create(cache_file)?;
-    file.write_all(serde_json::to_string(entries)?.as_bytes())?;
-    Ok(())
+/// Runs `passwd_cmd` (the same shell command handed to himalaya) and
+/// returns its trimmed stdout, so the raw CONDSTORE session can authenticate
+/// the same way himalaya's backend does.
+fn resolve_passwd_cmd(passwd_cmd: &str) -> Result<String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(passwd_cmd)
+        .output()?;
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
 }
 
-async fn load_from_cache(cache_file: &str) -> Result<Vec<Entry>> {
-    let file = File::open(cache_file)?;
-    let entries: Vec<Entry> = serde_json::from_reader(file)?;
-    Ok(entries)
+/// Attempts an incremental CONDSTORE sync against `folder`. Returns
+/// `Ok(None)` when `UIDVALIDITY` no longer matches (caller should do a full
+/// resync), or `Err` when CONDSTORE itself isn't usable (connection/auth
+/// failure or the server doesn't support it).
+fn sync_condstore(
+    host: &str,
+    port: u16,
+    login: &str,
+    credential: &Credential,
+    folder: &str,
+    uidvalidity: u32,
+    highest_modseq: u64,
+) -> Result<Option<(sync::MailboxState, Vec<Entry>)>> {
+    let mut session = connect_for(host, port, login, credential)?;
+    let state = sync::select_condstore(&mut session, folder)?;
+    if state.uidvalidity != uidvalidity {
+        return Ok(None);
+    }
+    let changed = sync::fetch_changed_since(&mut session, highest_modseq)?;
+    Ok(Some((state, changed)))
+}
+
+/// Records the current `UIDVALIDITY`/`HIGHESTMODSEQ` after a full resync, so
+/// the next run can go incremental. Best-effort: `None` just means the next
+/// run falls back to full pagination again.
+fn sync_condstore_baseline(
+    host: &str,
+    port: u16,
+    login: &str,
+    credential: &Credential,
+    folder: &str,
+) -> Result<Option<sync::MailboxState>> {
+    let mut session = connect_for(host, port, login, credential)?;
+    Ok(Some(sync::select_condstore(&mut session, folder)?))
 }
@@ -0,0 +1,202 @@
+//! Envelope sources other than live IMAP, so stats can be computed offline.
+//!
+//! `Source` is the `--source` CLI value (`imap`, `maildir:PATH`,
+//! `mbox:PATH`); `EnvelopeSource` is the trait each non-IMAP source
+//! implements, analogous to the `Backend` trait himalaya already uses for
+//! the IMAP path. Unlike IMAP-sourced `Entry` values, local sources keep the
+//! raw message bytes around (`Entry::raw`) so `--export-mbox` has something
+//! to write back out.
+
+use crate::Entry;
+use anyhow::{Context, Result};
+use mailparse::MailHeaderMap;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(Clone, Debug)]
+pub enum Source {
+    Imap,
+    Maildir(PathBuf),
+    Mbox(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceParseError(String);
+
+impl fmt::Display for SourceParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SourceParseError {}
+
+impl FromStr for Source {
+    type Err = SourceParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "imap" {
+            return Ok(Source::Imap);
+        }
+        if let Some(path) = s.strip_prefix("maildir:") {
+            return Ok(Source::Maildir(PathBuf::from(path)));
+        }
+        if let Some(path) = s.strip_prefix("mbox:") {
+            return Ok(Source::Mbox(PathBuf::from(path)));
+        }
+        Err(SourceParseError(format!(
+            "invalid --source '{}', expected 'imap', 'maildir:PATH', or 'mbox:PATH'",
+            s
+        )))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Imap => write!(f, "imap"),
+            Source::Maildir(p) => write!(f, "maildir:{}", p.display()),
+            Source::Mbox(p) => write!(f, "mbox:{}", p.display()),
+        }
+    }
+}
+
+pub trait EnvelopeSource {
+    fn list_entries(&self) -> Result<Vec<Entry>>;
+}
+
+pub struct MaildirSource {
+    pub path: PathBuf,
+}
+
+impl EnvelopeSource for MaildirSource {
+    fn list_entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for subdir in ["cur", "new"] {
+            let dir = self.path.join(subdir);
+            let Ok(read_dir) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for file in read_dir {
+                let file = file?;
+                if !file.file_type()?.is_file() {
+                    continue;
+                }
+                let raw = fs::read(file.path())
+                    .with_context(|| format!("reading maildir message {:?}", file.path()))?;
+                entries.push(entry_from_raw_message(&raw)?);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+pub struct MboxSource {
+    pub path: PathBuf,
+}
+
+impl EnvelopeSource for MboxSource {
+    fn list_entries(&self) -> Result<Vec<Entry>> {
+        let data = fs::read(&self.path)
+            .with_context(|| format!("reading mbox file {:?}", self.path))?;
+        split_mbox(&data)
+            .into_iter()
+            .map(|raw| entry_from_raw_message(raw))
+            .collect()
+    }
+}
+
+/// Splits an mbox file on its `From ` separator lines.
+fn split_mbox(data: &[u8]) -> Vec<&[u8]> {
+    let mut messages = Vec::new();
+    let mut start = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let line_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| pos + i + 1)
+            .unwrap_or(data.len());
+        let line = &data[pos..line_end];
+        if line.starts_with(b"From ") {
+            if let Some(s) = start {
+                messages.push(&data[s..pos]);
+            }
+            start = Some(line_end);
+        }
+        pos = line_end;
+    }
+    if let Some(s) = start {
+        messages.push(&data[s..]);
+    }
+    messages
+}
+
+fn entry_from_raw_message(raw: &[u8]) -> Result<Entry> {
+    let parsed = mailparse::parse_mail(raw).context("parsing message")?;
+    let headers = parsed.get_headers();
+    let from_addr = headers
+        .get_first_value("From")
+        .map(|v| extract_addr(&v))
+        .unwrap_or_default();
+    let subject = headers.get_first_value("Subject").unwrap_or_default();
+    let message_id = headers.get_first_value("Message-ID").unwrap_or_default();
+    let date = headers
+        .get_first_value("Date")
+        .and_then(|v| chrono::DateTime::parse_from_rfc2822(v.trim()).ok())
+        .unwrap_or_else(|| chrono::DateTime::parse_from_rfc3339("1970-01-01T00:00:00+00:00").unwrap());
+    Ok(Entry {
+        id: message_id.clone(),
+        message_id,
+        from_addr,
+        subject,
+        date,
+        raw: Some(raw.to_vec()),
+    })
+}
+
+/// `From` headers are often `Display Name <addr@host>`; extract just the
+/// address, matching the plain `addr` strings IMAP envelopes already use.
+fn extract_addr(from_header: &str) -> String {
+    if let (Some(start), Some(end)) = (from_header.find('<'), from_header.find('>')) {
+        if start < end {
+            return from_header[start + 1..end].to_string();
+        }
+    }
+    from_header.trim().to_string()
+}
+
+/// Writes `entries` out as a valid mbox file: one `From ` separator line per
+/// message, `>`-escaping any body line that would otherwise look like a new
+/// separator, and LF line endings throughout.
+pub fn export_mbox(path: &Path, entries: &[&Entry]) -> Result<()> {
+    let mut out = Vec::new();
+    for entry in entries {
+        let Some(raw) = &entry.raw else {
+            eprintln!(
+                "Skipping {} in mbox export: no raw message available (not a local source entry)",
+                entry.message_id
+            );
+            continue;
+        };
+        out.extend_from_slice(
+            format!("From {} {}\n", entry.from_addr, mbox_date(entry)).as_bytes(),
+        );
+        let body = String::from_utf8_lossy(raw).replace("\r\n", "\n");
+        for line in body.split('\n') {
+            if line.starts_with("From ") {
+                out.push(b'>');
+            }
+            out.extend_from_slice(line.as_bytes());
+            out.push(b'\n');
+        }
+    }
+    fs::write(path, out).with_context(|| format!("writing mbox export to {:?}", path))
+}
+
+fn mbox_date(entry: &Entry) -> String {
+    entry.date.format("%a %b %e %T %Y").to_string()
+}